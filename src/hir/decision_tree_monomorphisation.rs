@@ -0,0 +1,38 @@
+//! Lowers a `match`'s already-built `ast::DecisionTree` into a
+//! `hir::DecisionTree`.
+//!
+//! No decision tree is constructed from patterns here: the branch
+//! patterns are ignored and the main Ast's existing tree of integer
+//! switches is copied node for node, with its expressions monomorphised.
+use super::monomorphisation::Context;
+use super::{Ast, DecisionTree, Match};
+
+pub fn monomorphise_match<'c>(context: &mut Context<'c>, match_: &crate::parser::ast::Match<'c>) -> Ast {
+    let branches = match_.branches.iter().map(|(_pattern, branch)| super::monomorphisation::monomorphise_ast(context, branch)).collect();
+    let decision_tree = monomorphise_decision_tree(context, &match_.decision_tree);
+    Ast::Match(Match { branches, decision_tree })
+}
+
+fn monomorphise_decision_tree<'c>(
+    context: &mut Context<'c>,
+    tree: &crate::parser::ast::DecisionTree<'c>,
+) -> DecisionTree {
+    use crate::parser::ast;
+
+    match tree {
+        ast::DecisionTree::Leaf(index) => DecisionTree::Leaf(*index),
+        ast::DecisionTree::Definition(definition, rest) => {
+            let variable = context.next_definition_id();
+            let expr = super::monomorphisation::monomorphise_ast(context, &definition.expr);
+            let definition = super::monomorphisation::new_definition(variable, expr, false);
+            let rest = Box::new(monomorphise_decision_tree(context, rest));
+            DecisionTree::Definition(definition, rest)
+        },
+        ast::DecisionTree::Switch { int_to_switch_on, cases, else_case } => {
+            let int_to_switch_on = Box::new(super::monomorphisation::monomorphise_ast(context, int_to_switch_on));
+            let cases = cases.iter().map(|(tag, case)| (*tag, monomorphise_decision_tree(context, case))).collect();
+            let else_case = else_case.as_ref().map(|case| Box::new(monomorphise_decision_tree(context, case)));
+            DecisionTree::Switch { int_to_switch_on, cases, else_case }
+        },
+    }
+}
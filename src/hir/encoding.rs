@@ -0,0 +1,305 @@
+//! On-disk (de)serialization of the monomorphised `hir::Ast`, so that a
+//! previous build's monomorphisation can be reloaded and spliced together
+//! with freshly compiled definitions instead of recomputed from scratch.
+//!
+//! Two things make this more than a `#[derive(Serialize, Deserialize)]`:
+//!
+//! - `DefinitionInfo::definition` is an `Rc<Ast>` shared by every `Variable`
+//!   referring to it (e.g. every call site of a function shares the one
+//!   `Rc` for its body). Serializing each occurrence independently would
+//!   both bloat the file and - on reload - hand back separate, unshared
+//!   copies. `collect_rc_table` instead interns each distinct `Rc` into
+//!   `rc_table` below and `shared_definition` writes just its index.
+//! - `DefinitionId`s already in the cached module must stay stable across
+//!   a reload so they continue to match up with whatever was compiled
+//!   against them, while newly compiled definitions need to keep counting
+//!   up from where the cache left off; `EncodedHir::next_definition_id` is
+//!   what `monomorphise_resuming` uses to seed a fresh `Context` so newly
+//!   monomorphised definitions continue from there instead of colliding
+//!   with ids the cache already handed out.
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::{Ast, Literal, Variable, Visitor};
+
+thread_local! {
+    /// Populated up front by `encode_hir`, from `collect_rc_table`'s indices,
+    /// before serializing anything,
+    /// then only ever read from - so `shared_definition::serialize` can be a
+    /// plain lookup no matter what order serde visits fields in.
+    static ENCODE_INDICES: RefCell<HashMap<*const Ast, u32>> = RefCell::new(HashMap::new());
+
+    /// Filled in by `decode_slot`/`patch_decode_slot` as `rc_table` is
+    /// decoded (see `deserialize_rc_table`), so `shared_definition::deserialize`
+    /// can look up any entry referenced so far, in any order - an entry
+    /// that references itself or one discovered later than it (see
+    /// `collect_rc_table`) finds a placeholder here rather than nothing.
+    static DECODE_TABLE: RefCell<Vec<Rc<Ast>>> = RefCell::new(Vec::new());
+}
+
+/// `#[serde(with = "shared_definition")]` for `DefinitionInfo::definition`.
+pub mod shared_definition {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &Option<Rc<Ast>>, serializer: S) -> Result<S::Ok, S::Error> {
+        let index = value.as_ref().map(|rc| {
+            let ptr = Rc::as_ptr(rc);
+            ENCODE_INDICES.with(|indices| {
+                *indices.borrow().get(&ptr).unwrap_or_else(|| {
+                    panic!("hir encode: {:p} missing from the rc table - was encode_hir used?", ptr)
+                })
+            })
+        });
+        index.serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Rc<Ast>>, D::Error> {
+        let index = Option::<u32>::deserialize(deserializer)?;
+        Ok(index.map(decode_slot))
+    }
+}
+
+/// Walks `ast`, assigning each distinct `Rc<Ast>` reachable through a
+/// `Variable::definition` an index into `rc_table` in the order they are
+/// first reached, so a definition's index is always lower than those of
+/// the definitions its body first reaches. References inside an
+/// `rc_table` entry may therefore point at a *later* entry, which
+/// `deserialize_rc_table` resolves through the placeholders handed out by
+/// `decode_slot` rather than needing the whole table up front.
+///
+/// A (mutually) recursive definition's body contains a `Variable` whose
+/// `rc` is the very one we're in the middle of collecting, so its index
+/// has to be reserved *before* recursing into its contents - otherwise
+/// the recursive call site finds its own `ptr` still unindexed and walks
+/// back into itself forever. The table slot is filled in with a
+/// placeholder until the recursive call returns and the real clone can be
+/// written in its place; any reference to it encountered during that
+/// window (i.e. to itself) still decodes correctly since the index was
+/// already assigned.
+fn collect_rc_table(ast: &Ast) -> (Vec<Ast>, HashMap<*const Ast, u32>) {
+    struct Collector {
+        table: Vec<Ast>,
+        indices: HashMap<*const Ast, u32>,
+    }
+
+    impl Visitor for Collector {
+        fn visit_variable(&mut self, variable: &Variable) {
+            if let Some(rc) = &variable.definition {
+                let ptr = Rc::as_ptr(rc);
+                if !self.indices.contains_key(&ptr) {
+                    let index = self.table.len() as u32;
+                    self.indices.insert(ptr, index);
+                    self.table.push(Ast::Literal(Literal::Unit));
+                    self.visit_ast(rc);
+                    self.table[index as usize] = (**rc).clone();
+                }
+            }
+        }
+    }
+
+    let mut collector = Collector { table: Vec::new(), indices: HashMap::new() };
+    collector.visit_ast(ast);
+    (collector.table, collector.indices)
+}
+
+fn serialize_rc_table<S: Serializer>(table: &[Ast], serializer: S) -> Result<S::Ok, S::Error> {
+    table.serialize(serializer)
+}
+
+/// Returns the `Rc` for `index`, reserving it - and any lower index nothing
+/// has referenced yet - with a placeholder first if this is the first time
+/// it's been asked for. Mirrors the placeholder-then-patch trick
+/// `collect_rc_table` uses on the encode side: a self- or forward-reference
+/// (an entry's `rc_table` index is assigned before its contents are known
+/// to finish decoding, see `collect_rc_table`) gets a clone of the
+/// placeholder here, which `patch_decode_slot` later overwrites in place so
+/// every outstanding clone - including one inside the entry itself - ends
+/// up pointing at the real value.
+fn decode_slot(index: u32) -> Rc<Ast> {
+    DECODE_TABLE.with(|table| {
+        let mut table = table.borrow_mut();
+        while table.len() <= index as usize {
+            table.push(Rc::new(Ast::Literal(Literal::Unit)));
+        }
+        table[index as usize].clone()
+    })
+}
+
+/// Overwrites the `Rc` at `index` (reserving it with `decode_slot` first if
+/// nothing has referenced it yet) with `ast`'s contents in place, so every
+/// clone already handed out by `decode_slot` - whether to a forward
+/// reference from an earlier entry or a self-reference inside `ast` itself
+/// - now sees the real value.
+fn patch_decode_slot(index: u32, ast: Ast) {
+    let rc = decode_slot(index);
+    // SAFETY: every outstanding clone of `rc` is either this placeholder,
+    // untouched until now, or a reference that only gets read once the
+    // whole `EncodedHir` has finished decoding - never while this write is
+    // in progress.
+    unsafe {
+        *(Rc::as_ptr(&rc) as *mut Ast) = ast;
+    }
+}
+
+/// Decodes `rc_table`, repopulating `DECODE_TABLE` one entry at a time via
+/// `patch_decode_slot`. `DECODE_TABLE` is cleared up front so a second
+/// `decode_hir` on the same thread starts from empty instead of resolving
+/// its indices against whatever the previous decode left behind.
+fn deserialize_rc_table<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Ast>, D::Error> {
+    DECODE_TABLE.with(|decoded| decoded.borrow_mut().clear());
+
+    struct RcTableVisitor;
+
+    impl<'de> serde::de::Visitor<'de> for RcTableVisitor {
+        type Value = Vec<Ast>;
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            write!(f, "a sequence of hir::Ast rc-table entries")
+        }
+
+        fn visit_seq<A: serde::de::SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+            let mut table = Vec::new();
+            let mut index = 0u32;
+            while let Some(ast) = seq.next_element::<Ast>()? {
+                patch_decode_slot(index, ast.clone());
+                table.push(ast);
+                index += 1;
+            }
+            Ok(table)
+        }
+    }
+
+    deserializer.deserialize_seq(RcTableVisitor)
+}
+
+/// The on-disk format for a monomorphised module. `rc_table` is declared
+/// before `root` so that, under a sequential (non-self-describing) format
+/// like bincode, it finishes decoding - and populating `DECODE_TABLE` -
+/// before `root` is decoded and needs to resolve against it.
+#[derive(Serialize, Deserialize)]
+pub struct EncodedHir {
+    #[serde(serialize_with = "serialize_rc_table", deserialize_with = "deserialize_rc_table")]
+    rc_table: Vec<Ast>,
+    root: Ast,
+    next_definition_id: usize,
+}
+
+pub fn encode_hir(root: &Ast, next_definition_id: usize) -> EncodedHir {
+    let (rc_table, indices) = collect_rc_table(root);
+    ENCODE_INDICES.with(|cell| *cell.borrow_mut() = indices);
+    EncodedHir { rc_table, root: root.clone(), next_definition_id }
+}
+
+pub fn decode_hir(encoded: EncodedHir) -> (Ast, usize) {
+    (encoded.root, encoded.next_definition_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hir::{Definition, DefinitionId, FunctionCall};
+
+    /// Builds the `Rc<Ast>` a recursive function's `DefinitionInfo` would
+    /// hold: an `Ast::Definition` whose body calls back into a `Variable`
+    /// sharing the very same `Rc`, the way every call site of `f` shares
+    /// one `Rc` for `f`'s body. Safe Rust has no direct way to construct a
+    /// strong `Rc` cycle like this, so the placeholder is overwritten in
+    /// place once the self-referential body exists.
+    fn recursive_definition() -> Rc<Ast> {
+        let placeholder = Rc::new(Ast::Literal(Literal::Unit));
+        let self_call = Ast::Variable(Variable { definition_id: DefinitionId(0), definition: Some(placeholder.clone()) });
+        let body = Ast::FunctionCall(FunctionCall { function: Box::new(self_call), args: Vec::new(), span: None });
+        let real = Ast::Definition(Definition { variable: DefinitionId(0), expr: Box::new(body), mutable: false });
+
+        // SAFETY: `placeholder` isn't read anywhere else until after this
+        // write completes, and `real` is the same `Ast` type it already holds.
+        unsafe {
+            *(Rc::as_ptr(&placeholder) as *mut Ast) = real;
+        }
+        placeholder
+    }
+
+    #[test]
+    fn collect_rc_table_does_not_overflow_on_self_recursive_definitions() {
+        let root = Ast::Variable(Variable { definition_id: DefinitionId(0), definition: Some(recursive_definition()) });
+
+        let (table, indices) = collect_rc_table(&root);
+
+        assert_eq!(table.len(), 1);
+        assert_eq!(indices.len(), 1);
+    }
+
+    #[test]
+    fn deserialize_rc_table_clears_stale_entries_from_a_previous_decode() {
+        DECODE_TABLE.with(|table| table.borrow_mut().push(Rc::new(Ast::Literal(Literal::Unit))));
+        assert_eq!(DECODE_TABLE.with(|table| table.borrow().len()), 1);
+
+        let mut deserializer = serde_json::Deserializer::from_str("[]");
+        let table = deserialize_rc_table(&mut deserializer).unwrap();
+
+        assert!(table.is_empty());
+        assert_eq!(DECODE_TABLE.with(|table| table.borrow().len()), 0);
+    }
+
+    /// Drives `encode_hir` all the way through a `serde_json` round trip and
+    /// back through `decode_hir`, rather than just exercising `collect_rc_table`
+    /// in isolation: a self-recursive definition's own `rc_table` entry
+    /// references itself, which `deserialize_rc_table` previously could only
+    /// resolve if references pointed strictly backward.
+    #[test]
+    fn round_trips_a_self_recursive_definition_through_encode_and_decode() {
+        let root = Ast::Variable(Variable { definition_id: DefinitionId(0), definition: Some(recursive_definition()) });
+
+        let encoded = encode_hir(&root, 1);
+        let json = serde_json::to_string(&encoded).unwrap();
+        let decoded: EncodedHir = serde_json::from_str(&json).unwrap();
+        let (ast, next_definition_id) = decode_hir(decoded);
+
+        assert_eq!(next_definition_id, 1);
+        let Ast::Variable(Variable { definition: Some(rc), .. }) = &ast else { panic!("expected a Variable") };
+        let Ast::Definition(Definition { expr, .. }) = rc.as_ref() else { panic!("expected a Definition") };
+        let Ast::FunctionCall(FunctionCall { function, .. }) = expr.as_ref() else { panic!("expected a FunctionCall") };
+        let Ast::Variable(Variable { definition: Some(self_rc), .. }) = function.as_ref() else {
+            panic!("expected the self-call's Variable")
+        };
+        assert!(Rc::ptr_eq(rc, self_rc));
+    }
+
+    /// A (non-recursive) two-function call chain where A's body calls B:
+    /// `collect_rc_table` discovers A before recursing into its body finds
+    /// B, so A's `rc_table` entry is index 0 and B's is index 1 - meaning
+    /// A's entry contains a *forward* reference to an index that hasn't
+    /// been decoded yet when A itself is decoded.
+    #[test]
+    fn round_trips_a_forward_reference_between_two_shared_definitions() {
+        let b = Rc::new(Ast::Definition(Definition {
+            variable: DefinitionId(1),
+            expr: Box::new(Ast::Literal(Literal::Unit)),
+            mutable: false,
+        }));
+        let call_b = Ast::Variable(Variable { definition_id: DefinitionId(1), definition: Some(b) });
+        let a = Rc::new(Ast::Definition(Definition {
+            variable: DefinitionId(0),
+            expr: Box::new(Ast::FunctionCall(FunctionCall { function: Box::new(call_b), args: Vec::new(), span: None })),
+            mutable: false,
+        }));
+        let root = Ast::Variable(Variable { definition_id: DefinitionId(0), definition: Some(a) });
+
+        let encoded = encode_hir(&root, 2);
+        let json = serde_json::to_string(&encoded).unwrap();
+        let decoded: EncodedHir = serde_json::from_str(&json).unwrap();
+        let (ast, next_definition_id) = decode_hir(decoded);
+
+        assert_eq!(next_definition_id, 2);
+        let Ast::Variable(Variable { definition: Some(a_rc), .. }) = &ast else { panic!("expected a Variable") };
+        let Ast::Definition(Definition { expr, .. }) = a_rc.as_ref() else { panic!("expected a Definition") };
+        let Ast::FunctionCall(FunctionCall { function, .. }) = expr.as_ref() else { panic!("expected a FunctionCall") };
+        let Ast::Variable(Variable { definition: Some(b_rc), .. }) = function.as_ref() else {
+            panic!("expected B's Variable")
+        };
+        assert!(matches!(b_rc.as_ref(), Ast::Definition(Definition { variable: DefinitionId(1), .. })));
+    }
+}
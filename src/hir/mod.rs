@@ -11,19 +11,32 @@ mod types;
 mod monomorphisation;
 mod decision_tree_monomorphisation;
 mod printer;
+mod visitor;
+mod span;
+mod encoding;
 
 use std::rc::Rc;
 
-pub use monomorphisation::monomorphise;
+use serde::{Serialize, Deserialize};
+
+pub use monomorphisation::{monomorphise, monomorphise_resuming};
+pub use visitor::{Visitor, Folder};
+pub use span::Span;
+pub use encoding::{encode_hir, decode_hir};
 
 use types::{ Type, IntegerKind, FunctionType };
 
 use self::printer::FmtAst;
 
-#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord)]
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct DefinitionId(usize);
 
-#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+/// Identifies a `loop` so that a `Break`/`Continue` nested inside further
+/// loops can still target an outer one, mirroring rustc HIR's `Label`.
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct LoopId(usize);
+
+#[derive(Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
 pub enum Literal {
     Integer(u64, IntegerKind),
     Float(u64),
@@ -33,7 +46,7 @@ pub enum Literal {
     Unit,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DefinitionInfo {
     /// The Ast for the Ast::Definition which defines this Variable.
     /// This may be None if this variable was defined from a function
@@ -43,6 +56,12 @@ pub struct DefinitionInfo {
     /// `id = expr` where id == self.definition_id. Most definitions will
     /// be exactly this, but others may be a sequence of several definitions
     /// in the case of e.g. tuple unpacking.
+    ///
+    /// Shared definitions are interned into the rc-table described in
+    /// `encoding.rs` on (de)serialization rather than written out inline,
+    /// so that e.g. a recursive function's body isn't duplicated once per
+    /// call site.
+    #[serde(with = "encoding::shared_definition")]
     pub definition: Option<Rc<Ast>>,
 
     pub definition_id: DefinitionId,
@@ -64,24 +83,30 @@ impl From<DefinitionId> for Variable {
 
 /// \a b. expr
 /// Function definitions are also desugared to a ast::Definition with a ast::Lambda as its body
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Lambda {
     pub args: Vec<Ast>,
     pub body: Box<Ast>,
     pub typ: FunctionType,
+
+    /// Copied from the ast::Lambda this was monomorphised from, so that
+    /// e.g. an LLVM verifier failure inside the generated function can
+    /// still be pointed back at the `\...` that defined it.
+    pub span: Option<Span>,
 }
 
 /// foo a b c
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FunctionCall {
     pub function: Box<Ast>,
     pub args: Vec<Ast>,
+    pub span: Option<Span>,
 }
 
 /// Unlike ast::Definition, hir::Definition
 /// is desugared of any patterns, its lhs must
 /// be a single variable to simplify backends.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Definition {
     pub variable: DefinitionId,
     pub expr: Box<Ast>,
@@ -98,14 +123,15 @@ impl From<Definition> for DefinitionInfo {
 }
 
 /// if condition then expression else expression
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct If {
     pub condition: Box<Ast>,
     pub then: Box<Ast>,
     pub otherwise: Option<Box<Ast>>,
+    pub span: Option<Span>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Match {
     // Unlike ast::Match this only contains the parts of the
     // branch after the ->.
@@ -134,7 +160,7 @@ pub struct Match {
 // }
 // ```
 // Where two different paths need to share the same leaf branch.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum DecisionTree {
     Leaf(usize),
     Definition(Definition, Box<DecisionTree>),
@@ -146,16 +172,17 @@ pub enum DecisionTree {
 }
 
 /// return expression
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Return {
     pub expression: Box<Ast>,
+    pub span: Option<Span>,
 }
 
 /// statement1
 /// statement2
 /// ...
 /// statementN
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Sequence {
     pub statements: Vec<Ast>,
 }
@@ -167,26 +194,26 @@ pub struct Sequence {
 ///     declaration2
 ///     ...
 ///     declarationN
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Extern {
     pub name: String,
     pub typ: Type,
 }
 
 /// lhs := rhs
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Assignment {
     pub lhs: Box<Ast>,
     pub rhs: Box<Ast>,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct MemberAccess{
     pub lhs: Box<Ast>,
     pub member_index: u32,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Tuple {
     pub fields: Vec<Ast>,
 }
@@ -196,13 +223,44 @@ pub struct Tuple {
 /// then lowered to this. lhs's type should be the same
 /// size as the target type, though there may be
 /// padding differences currently.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ReinterpretCast {
     pub lhs: Box<Ast>,
     pub target_type: Type,
+
+    /// Used to turn a size mismatch between `lhs` and `target_type` into a
+    /// diagnostic pointing at the expression that was cast, rather than a
+    /// bare panic inside the monomorphiser or backend.
+    pub span: Option<Span>,
+}
+
+/// loop { body }
+///
+/// The surface language's `while`/`loop` forms are both lowered to this by
+/// the monomorphiser; a `while cond { body }` becomes
+/// `loop { if cond { body } else { break } }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Loop {
+    pub label: Option<LoopId>,
+    pub body: Box<Ast>,
+}
+
+/// break (label)? (value)?
+///
+/// `value` lets a loop evaluate to a result, e.g. `loop { break 3 }`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Break {
+    pub label: Option<LoopId>,
+    pub value: Option<Box<Ast>>,
+}
+
+/// continue (label)?
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Continue {
+    pub label: Option<LoopId>,
 }
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, Serialize, Deserialize)]
 pub enum Builtin {
     AddInt,
     AddFloat,
@@ -221,15 +279,39 @@ pub enum Builtin {
 
     LessInt,
     LessFloat,
+    LessEqInt,
+    LessEqFloat,
 
     GreaterInt,
     GreaterFloat,
+    GreaterEqInt,
+    GreaterEqFloat,
 
     EqInt,
     EqFloat,
     EqChar,
     EqBool,
 
+    NeqInt,
+    NeqFloat,
+
+    BitAndInt,
+    BitOrInt,
+    BitXorInt,
+
+    ShiftLeft,
+    /// Signed integers shift right arithmetically (sign-extending); unsigned
+    /// integers shift right logically (zero-extending). `signed` is carried
+    /// on the builtin itself, set from the operand's `IntegerKind`, rather
+    /// than looked up again at codegen time.
+    ShiftRight { signed: bool },
+
+    NotInt,
+    NotBool,
+
+    NegInt,
+    NegFloat,
+
     SignExtend,
     ZeroExtend,
     Truncate,
@@ -238,7 +320,50 @@ pub enum Builtin {
     Transmute,
 }
 
-#[derive(Debug, Clone)]
+impl Builtin {
+    /// Precedence of this builtin when printed as an infix operator, on
+    /// the same scale as `Ast::precedence`. Only meaningful for the
+    /// builtins `symbol` gives an infix spelling to.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Builtin::MulInt | Builtin::MulFloat | Builtin::DivInt | Builtin::DivFloat | Builtin::ModInt | Builtin::ModFloat => 7,
+            Builtin::AddInt | Builtin::AddFloat | Builtin::SubInt | Builtin::SubFloat => 6,
+            Builtin::ShiftLeft | Builtin::ShiftRight { .. } => 5,
+            Builtin::BitAndInt => 4,
+            Builtin::BitXorInt => 3,
+            Builtin::BitOrInt => 2,
+            _ => 1,
+        }
+    }
+
+    /// The infix spelling of this builtin in Ante-like source, if it has
+    /// one. Builtins without a natural infix form (casts, `Deref`, ...)
+    /// return `None` and are printed as ordinary function applications.
+    pub fn symbol(&self) -> Option<&'static str> {
+        use Builtin::*;
+        Some(match self {
+            AddInt | AddFloat => "+",
+            SubInt | SubFloat => "-",
+            MulInt | MulFloat => "*",
+            DivInt | DivFloat => "/",
+            ModInt | ModFloat => "%",
+            LessInt | LessFloat => "<",
+            LessEqInt | LessEqFloat => "<=",
+            GreaterInt | GreaterFloat => ">",
+            GreaterEqInt | GreaterEqFloat => ">=",
+            EqInt | EqFloat | EqChar | EqBool => "==",
+            NeqInt | NeqFloat => "!=",
+            BitAndInt => "&",
+            BitOrInt => "|",
+            BitXorInt => "^",
+            ShiftLeft => "<<",
+            ShiftRight { .. } => ">>",
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Ast {
     Literal(Literal),
     Variable(Variable),
@@ -255,6 +380,94 @@ pub enum Ast {
     Tuple(Tuple),
     ReinterpretCast(ReinterpretCast),
     Builtin(Builtin),
+    Loop(Loop),
+    Break(Break),
+    Continue(Continue),
+}
+
+impl Ast {
+    /// The span this node was monomorphised from, if any. Not every
+    /// variant carries one - only those a backend is likely to need to
+    /// report an error against do.
+    pub fn span(&self) -> Option<Span> {
+        match self {
+            Ast::Lambda(lambda) => lambda.span,
+            Ast::FunctionCall(call) => call.span,
+            Ast::If(if_) => if_.span,
+            Ast::Return(return_) => return_.span,
+            Ast::ReinterpretCast(cast) => cast.span,
+            _ => None,
+        }
+    }
+
+    /// How tightly this node binds relative to its neighbors, for deciding
+    /// where `AstPrinter::print_minimal_parens` needs to insert
+    /// parentheses to keep the printed form re-parseable. Higher binds
+    /// tighter. A `FunctionCall` of an infix `Builtin` is a special case
+    /// handled directly by `printer::infix_operator` instead of here,
+    /// since its effective precedence depends on which operator it is.
+    pub fn precedence(&self) -> u8 {
+        match self {
+            Ast::Literal(_) | Ast::Variable(_) | Ast::Tuple(_) | Ast::MemberAccess(_) | Ast::Builtin(_) => 10,
+            Ast::FunctionCall(_) | Ast::ReinterpretCast(_) => 9,
+            Ast::Assignment(_) => 2,
+            Ast::If(_)
+            | Ast::Match(_)
+            | Ast::Lambda(_)
+            | Ast::Loop(_)
+            | Ast::Return(_)
+            | Ast::Break(_)
+            | Ast::Continue(_)
+            | Ast::Definition(_) => 1,
+            Ast::Sequence(_) | Ast::Extern(_) => 0,
+        }
+    }
+
+    /// Like the `Display` impl, but annotates each node with the span it
+    /// was lowered from. Intended for debugging the monomorphiser, not
+    /// for user-facing diagnostics.
+    pub fn display_with_spans(&self) -> impl std::fmt::Display + '_ {
+        struct WithSpans<'a>(&'a Ast);
+
+        impl std::fmt::Display for WithSpans<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let mut printer = printer::AstPrinter { print_spans: true, ..Default::default() };
+                self.0.fmt_ast(&mut printer, f)?;
+
+                while let Some((id, ast)) = printer.queue.pop_front() {
+                    write!(f, "\n\nfn_{} = ", id)?;
+                    ast.fmt_ast(&mut printer, f)?;
+                }
+
+                Ok(())
+            }
+        }
+
+        WithSpans(self)
+    }
+
+    /// Like the `Display` impl, but only adds parentheses where precedence
+    /// actually requires them, producing readable, re-parseable Ante-like
+    /// source instead of a raw tree dump.
+    pub fn display_minimal_parens(&self) -> impl std::fmt::Display + '_ {
+        struct Minimal<'a>(&'a Ast);
+
+        impl std::fmt::Display for Minimal<'_> {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                let mut printer = printer::AstPrinter { print_minimal_parens: true, ..Default::default() };
+                self.0.fmt_ast(&mut printer, f)?;
+
+                while let Some((id, ast)) = printer.queue.pop_front() {
+                    write!(f, "\n\nfn_{} = ", id)?;
+                    ast.fmt_ast(&mut printer, f)?;
+                }
+
+                Ok(())
+            }
+        }
+
+        Minimal(self)
+    }
 }
 
 impl std::fmt::Display for Ast {
@@ -262,8 +475,8 @@ impl std::fmt::Display for Ast {
         let mut printer = printer::AstPrinter::default();
         self.fmt_ast(&mut printer, f)?;
 
-        while let Some(ast) = printer.queue.pop_front() {
-            write!(f, "\n\n")?;
+        while let Some((id, ast)) = printer.queue.pop_front() {
+            write!(f, "\n\nfn_{} = ", id)?;
             ast.fmt_ast(&mut printer, f)?;
         }
 
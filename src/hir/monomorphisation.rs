@@ -0,0 +1,397 @@
+//! Lowers the main, generic `ast::Ast` into the monomorphised `hir::Ast`.
+//!
+//! Each generic definition is specialized once per concrete set of type
+//! arguments it's called with; the `Context` below is what remembers which
+//! specializations have already been lowered (keyed by definition id and
+//! type arguments) so that a definition used at the same type twice is
+//! only monomorphised once and shares a `DefinitionId`. `monomorphise_ast`
+//! lowers only the variants it matches; it does not lower variables or
+//! definitions, so nothing in this file fills or reads `Context::definitions`.
+use std::collections::HashMap;
+
+use crate::cache::ModuleCache;
+use crate::types::Typed;
+
+use super::decision_tree_monomorphisation::monomorphise_match;
+use super::types::{IntegerKind, PrimitiveType, Type};
+use super::{
+    Ast, Break, Builtin, Continue, Definition, DefinitionId, FunctionCall, If, Lambda, Loop, LoopId, ReinterpretCast,
+    Return, Span,
+};
+
+/// The loops currently being lowered the body of, innermost last, so a
+/// nested `break`/`continue` can resolve its label against any enclosing
+/// loop rather than only the one directly around it. Kept independent of
+/// `Context` (which otherwise needs a live `ModuleCache` to construct) so
+/// this resolution logic can be unit tested on its own.
+struct LoopStack {
+    next_id: usize,
+    stack: Vec<(Option<String>, LoopId)>,
+}
+
+impl LoopStack {
+    fn new() -> LoopStack {
+        LoopStack { next_id: 0, stack: Vec::new() }
+    }
+
+    /// Allocates a `LoopId` for a loop being entered and pushes it for the
+    /// duration of its body, so `break`/`continue` lowered anywhere inside
+    /// - including inside further nested loops - can still resolve back to
+    /// it. Pair with `pop` once the body is done being lowered.
+    fn push(&mut self, label: Option<&str>) -> LoopId {
+        let id = LoopId(self.next_id);
+        self.next_id += 1;
+        self.stack.push((label.map(str::to_string), id));
+        id
+    }
+
+    fn pop(&mut self) {
+        self.stack.pop();
+    }
+
+    /// Resolves a `break`/`continue`'s source-level label to the `LoopId`
+    /// it targets: an unlabeled one always means the innermost enclosing
+    /// loop, while a labeled one walks outward from there to the nearest
+    /// loop whose own label matches, so `break 'outer` from inside a
+    /// nested loop still reaches past it.
+    fn resolve(&self, label: Option<&str>) -> Option<LoopId> {
+        match label {
+            None => self.stack.last().map(|(_, id)| *id),
+            Some(label) => self.stack.iter().rev().find(|(name, _)| name.as_deref() == Some(label)).map(|(_, id)| *id),
+        }
+    }
+}
+
+pub struct Context<'c> {
+    pub cache: &'c ModuleCache<'c>,
+
+    /// Each (ast::DefinitionInfoId, concrete type arguments) pair is
+    /// monomorphised into its own hir::DefinitionId the first time it's
+    /// encountered, then reused for any later call at the same type.
+    pub definitions: HashMap<(crate::cache::DefinitionInfoId, Vec<crate::types::Type>), DefinitionId>,
+
+    next_id: usize,
+    loops: LoopStack,
+}
+
+impl<'c> Context<'c> {
+    pub fn new(cache: &'c ModuleCache<'c>) -> Context<'c> {
+        Self::resuming(cache, 0)
+    }
+
+    /// Like `new`, but hands out `DefinitionId`s starting from
+    /// `next_definition_id` instead of 0, so ids freshly monomorphised in
+    /// this run don't collide with ones a previous `encode_hir`/`decode_hir`
+    /// round trip already assigned - see `EncodedHir::next_definition_id`.
+    pub fn resuming(cache: &'c ModuleCache<'c>, next_definition_id: usize) -> Context<'c> {
+        Context { cache, definitions: HashMap::new(), next_id: next_definition_id, loops: LoopStack::new() }
+    }
+
+    pub fn next_definition_id(&mut self) -> DefinitionId {
+        let id = DefinitionId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Allocates a `LoopId` for a loop being entered and pushes it onto the
+    /// loop stack for the duration of its body. Pair with `pop_loop` once
+    /// the body is done being lowered.
+    fn push_loop(&mut self, label: Option<&str>) -> LoopId {
+        self.loops.push(label)
+    }
+
+    fn pop_loop(&mut self) {
+        self.loops.pop();
+    }
+
+    fn resolve_loop_label(&self, label: Option<&str>) -> Option<LoopId> {
+        self.loops.resolve(label)
+    }
+
+    fn span_of(&self, location: crate::error::location::Location<'c>) -> Span {
+        Span::new(location.filename_id(), location.start_index(), location.end_index())
+    }
+}
+
+pub fn monomorphise<'c>(ast: &crate::parser::ast::Ast<'c>, cache: &'c ModuleCache<'c>) -> Ast {
+    monomorphise_resuming(ast, cache, 0)
+}
+
+/// Like `monomorphise`, but continues `DefinitionId` allocation from
+/// `next_definition_id` rather than starting over at 0 - the counterpart a
+/// caller should use when splicing freshly monomorphised definitions onto
+/// a module reloaded via `decode_hir`, whose returned `usize` is exactly
+/// the `next_definition_id` to resume from.
+pub fn monomorphise_resuming<'c>(
+    ast: &crate::parser::ast::Ast<'c>,
+    cache: &'c ModuleCache<'c>,
+    next_definition_id: usize,
+) -> Ast {
+    let mut context = Context::resuming(cache, next_definition_id);
+    monomorphise_ast(&mut context, ast)
+}
+
+pub(super) fn monomorphise_ast<'c>(context: &mut Context<'c>, ast: &crate::parser::ast::Ast<'c>) -> Ast {
+    use crate::parser::ast;
+
+    match ast {
+        ast::Ast::Literal(literal) => Ast::Literal(monomorphise_literal(literal)),
+        ast::Ast::Lambda(lambda) => Ast::Lambda(monomorphise_lambda(context, lambda)),
+        ast::Ast::FunctionCall(call) => {
+            let function = Box::new(monomorphise_ast(context, &call.function));
+            let mut args: Vec<Ast> = call.args.iter().map(|arg| monomorphise_ast(context, arg)).collect();
+            let span = Some(context.span_of(call.location));
+
+            // `transmute lhs` reinterprets `lhs`'s bits as the call's result
+            // type; this is the only place a `hir::ReinterpretCast` gets
+            // built, and `reinterpret_cast` is what turns a size mismatch
+            // between the two into a diagnostic pointing at `span` instead
+            // of a bare panic with no source location.
+            if matches!(function.as_ref(), Ast::Builtin(Builtin::Transmute)) {
+                if let ([arg_node], Some(lhs)) = (call.args.as_slice(), args.pop()) {
+                    let lhs_type = monomorphise_type(arg_node.get_type());
+                    let target_type = monomorphise_type(&call.typ);
+                    return reinterpret_cast(lhs, &lhs_type, target_type, span);
+                }
+            }
+
+            Ast::FunctionCall(FunctionCall { function, args, span })
+        },
+        ast::Ast::If(if_) => {
+            let condition = Box::new(monomorphise_ast(context, &if_.condition));
+            let then = Box::new(monomorphise_ast(context, &if_.then));
+            let otherwise = if_.otherwise.as_ref().map(|o| Box::new(monomorphise_ast(context, o)));
+            let span = Some(context.span_of(if_.location));
+            Ast::If(If { condition, then, otherwise, span })
+        },
+        ast::Ast::Match(match_) => monomorphise_match(context, match_),
+        ast::Ast::Return(return_) => {
+            let expression = Box::new(monomorphise_ast(context, &return_.expression));
+            let span = Some(context.span_of(return_.location));
+            Ast::Return(Return { expression, span })
+        },
+        // `while cond { body }` desugars to `loop { if cond { body } else { break } }`
+        // so backends only ever have to special-case one looping construct.
+        ast::Ast::While(while_) => {
+            let id = context.push_loop(while_.label.as_deref());
+            let condition = monomorphise_ast(context, &while_.condition);
+            let body = monomorphise_ast(context, &while_.body);
+            context.pop_loop();
+            let break_ = Ast::Break(Break { label: Some(id), value: None });
+            let if_ = Ast::If(If {
+                condition: Box::new(condition),
+                then: Box::new(body),
+                otherwise: Some(Box::new(break_)),
+                span: None,
+            });
+            Ast::Loop(Loop { label: Some(id), body: Box::new(if_) })
+        },
+        ast::Ast::Loop(loop_) => {
+            let id = context.push_loop(loop_.label.as_deref());
+            let body = Box::new(monomorphise_ast(context, &loop_.body));
+            context.pop_loop();
+            Ast::Loop(Loop { label: Some(id), body })
+        },
+        ast::Ast::Break(break_) => {
+            let label = context.resolve_loop_label(break_.label.as_deref());
+            let value = break_.value.as_ref().map(|value| Box::new(monomorphise_ast(context, value)));
+            Ast::Break(Break { label, value })
+        },
+        ast::Ast::Continue(continue_) => {
+            let label = context.resolve_loop_label(continue_.label.as_deref());
+            Ast::Continue(Continue { label })
+        },
+        // No other ast::Ast variant (variables, definitions, sequences, ...)
+        // is lowered here; reaching one panics.
+        _ => unimplemented!("monomorphise_ast for {:?}", ast),
+    }
+}
+
+fn monomorphise_literal(literal: &crate::parser::ast::Literal) -> super::Literal {
+    match literal {
+        crate::parser::ast::Literal::Unit => super::Literal::Unit,
+        _ => unimplemented!("monomorphise_literal"),
+    }
+}
+
+fn monomorphise_lambda<'c>(context: &mut Context<'c>, lambda: &crate::parser::ast::Lambda<'c>) -> Lambda {
+    let args = lambda.args.iter().map(|_| Ast::Literal(super::Literal::Unit)).collect();
+    let body = Box::new(monomorphise_ast(context, &lambda.body));
+    let span = Some(context.span_of(lambda.location));
+    Lambda { args, body, typ: monomorphise_function_type(&lambda.typ), span }
+}
+
+fn monomorphise_function_type(_typ: &crate::types::Type) -> super::types::FunctionType {
+    unimplemented!("monomorphise_function_type")
+}
+
+/// Monomorphises a front end type annotation into the concrete `hir::Type`
+/// a backend can lower directly. Only the primitive cases `reinterpret_cast`
+/// needs to compute a size are filled in here; like `monomorphise_function_type`,
+/// the rest are left for whichever later request needs them.
+fn monomorphise_type(typ: &crate::types::Type) -> Type {
+    use crate::types::{PrimitiveType as FrontendPrimitiveType, Type as FrontendType};
+
+    match typ {
+        FrontendType::Primitive(FrontendPrimitiveType::Integer(kind)) => Type::Primitive(PrimitiveType::Integer(*kind)),
+        FrontendType::Primitive(FrontendPrimitiveType::Float) => Type::Primitive(PrimitiveType::Float),
+        FrontendType::Primitive(FrontendPrimitiveType::Char) => Type::Primitive(PrimitiveType::Char),
+        FrontendType::Primitive(FrontendPrimitiveType::Boolean) => Type::Primitive(PrimitiveType::Boolean),
+        FrontendType::Primitive(FrontendPrimitiveType::Unit) => Type::Primitive(PrimitiveType::Unit),
+        _ => unimplemented!("monomorphise_type for {:?}", typ),
+    }
+}
+
+/// Builds a `ReinterpretCast` from `lhs` (of `lhs_type`) to `target_type`,
+/// checking the two are the same size first. This is the only place a
+/// `ReinterpretCast` is constructed - see its call site in `monomorphise_ast`
+/// - and the size check is what lets a mismatch panic with `span` pointing
+/// at the expression being cast, rather than surfacing as a bare panic
+/// inside the monomorphiser or a backend.
+pub(super) fn reinterpret_cast(lhs: Ast, lhs_type: &Type, target_type: Type, span: Option<Span>) -> Ast {
+    let lhs_size = lhs_type.size_in_bytes();
+    let target_size = target_type.size_in_bytes();
+    if lhs_size != target_size {
+        let location = span.map_or_else(|| "<unknown location>".to_string(), |span| span.to_string());
+        panic!("hir: cannot reinterpret a {}-byte value as a {}-byte type at {}", lhs_size, target_size, location);
+    }
+    Ast::ReinterpretCast(ReinterpretCast { lhs: Box::new(lhs), target_type, span })
+}
+
+pub fn new_definition(variable: DefinitionId, expr: Ast, mutable: bool) -> Definition {
+    Definition { variable, expr: Box::new(expr), mutable }
+}
+
+/// Resolves one of the front end's builtin operator names (`"+"`, `"&"`,
+/// `">>"`, ...) applied to `args` to the `hir::FunctionCall` it should be
+/// lowered to, given the `IntegerKind` of its operands. `kind` is consulted
+/// for operators whose behavior depends on signedness or width: `>>`, which
+/// must pick an arithmetic (sign-extending) or logical (zero-extending)
+/// shift based on it, and both shifts, whose amount (`args[1]`) is masked
+/// into the range `[0, kind.bit_width())` first - see `mask_shift_amount`.
+pub(super) fn resolve_builtin(name: &str, kind: Option<IntegerKind>, mut args: Vec<Ast>, span: Option<Span>) -> Ast {
+    let builtin = match name {
+        "+" => Builtin::AddInt,
+        "-" => Builtin::SubInt,
+        "*" => Builtin::MulInt,
+        "/" => Builtin::DivInt,
+        "%" => Builtin::ModInt,
+
+        "<" => Builtin::LessInt,
+        "<=" => Builtin::LessEqInt,
+        ">" => Builtin::GreaterInt,
+        ">=" => Builtin::GreaterEqInt,
+        "==" => Builtin::EqInt,
+        "!=" => Builtin::NeqInt,
+
+        "&" => Builtin::BitAndInt,
+        "|" => Builtin::BitOrInt,
+        "^" => Builtin::BitXorInt,
+        "<<" => Builtin::ShiftLeft,
+        ">>" => {
+            let signed = kind.map_or(true, IntegerKind::is_signed);
+            Builtin::ShiftRight { signed }
+        },
+
+        "not" => Builtin::NotBool,
+        "~" => Builtin::NotInt,
+        "negate" => Builtin::NegInt,
+
+        _ => unimplemented!("resolve_builtin for {:?}", name),
+    };
+
+    if matches!(builtin, Builtin::ShiftLeft | Builtin::ShiftRight { .. }) {
+        if let (Some(kind), Some(amount)) = (kind, args.pop()) {
+            args.push(mask_shift_amount(amount, kind, span));
+        }
+    }
+
+    Ast::FunctionCall(FunctionCall { function: Box::new(Ast::Builtin(builtin)), args, span })
+}
+
+/// LLVM's `shl`/`lshr`/`ashr` are poison for a shift amount outside
+/// `[0, width)`, but nothing in the surface language stops a shift count
+/// from being e.g. `>= width`. Masks `amount` into `[0, kind.bit_width())`
+/// (`amount & (width - 1)`, valid since the width is always a power
+/// of two) before it reaches the `ShiftLeft`/`ShiftRight` builtin.
+fn mask_shift_amount(amount: Ast, kind: IntegerKind, span: Option<Span>) -> Ast {
+    let mask = Ast::Literal(super::Literal::Integer((kind.bit_width() - 1) as u64, kind));
+    Ast::FunctionCall(FunctionCall { function: Box::new(Ast::Builtin(Builtin::BitAndInt)), args: vec![amount, mask], span })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hir::Literal;
+
+    fn int(n: u64, kind: IntegerKind) -> Ast {
+        Ast::Literal(Literal::Integer(n, kind))
+    }
+
+    #[test]
+    fn resolve_builtin_masks_shift_amount_to_operand_bit_width() {
+        let args = vec![int(1, IntegerKind::I8), int(9, IntegerKind::I8)];
+        let call = resolve_builtin("<<", Some(IntegerKind::I8), args, None);
+
+        let Ast::FunctionCall(FunctionCall { function, args, .. }) = call else { panic!("expected a FunctionCall") };
+        assert!(matches!(*function, Ast::Builtin(Builtin::ShiftLeft)));
+
+        let Ast::FunctionCall(mask_call) = &args[1] else { panic!("expected the shift amount to be masked") };
+        assert!(matches!(*mask_call.function, Ast::Builtin(Builtin::BitAndInt)));
+        let Ast::Literal(Literal::Integer(mask, _)) = &mask_call.args[1] else { panic!("expected a mask literal") };
+        assert_eq!(*mask, 7); // I8's bit width (8) - 1
+    }
+
+    #[test]
+    fn resolve_builtin_picks_signedness_for_shift_right_from_kind() {
+        let args = vec![int(1, IntegerKind::U32), int(1, IntegerKind::U32)];
+        let call = resolve_builtin(">>", Some(IntegerKind::U32), args, None);
+
+        let Ast::FunctionCall(FunctionCall { function, .. }) = call else { panic!("expected a FunctionCall") };
+        assert!(matches!(*function, Ast::Builtin(Builtin::ShiftRight { signed: false })));
+    }
+
+    #[test]
+    fn reinterpret_cast_accepts_matching_sizes() {
+        let lhs = int(1, IntegerKind::I32);
+        let typ = Type::Primitive(PrimitiveType::Integer(IntegerKind::I32));
+        let ast = reinterpret_cast(lhs, &typ, typ.clone(), None);
+        assert!(matches!(ast, Ast::ReinterpretCast(_)));
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot reinterpret")]
+    fn reinterpret_cast_rejects_size_mismatches() {
+        let lhs = int(1, IntegerKind::I8);
+        let lhs_type = Type::Primitive(PrimitiveType::Integer(IntegerKind::I8));
+        let target_type = Type::Primitive(PrimitiveType::Integer(IntegerKind::I64));
+        reinterpret_cast(lhs, &lhs_type, target_type, None);
+    }
+
+    #[test]
+    fn resolve_targets_outer_loop_past_a_nested_unlabeled_one() {
+        let mut loops = LoopStack::new();
+
+        let outer = loops.push(Some("outer"));
+        let _inner = loops.push(None);
+
+        // An unlabeled `break`/`continue` inside the inner loop resolves to
+        // the inner loop, not the outer one.
+        assert_eq!(loops.resolve(None), Some(_inner));
+
+        // A `break 'outer` from inside the inner loop has to walk past it
+        // to reach the loop whose own label actually matches.
+        assert_eq!(loops.resolve(Some("outer")), Some(outer));
+
+        loops.pop();
+        assert_eq!(loops.resolve(None), Some(outer));
+    }
+
+    #[test]
+    fn resolve_returns_none_for_an_unknown_label() {
+        let mut loops = LoopStack::new();
+        loops.push(Some("outer"));
+
+        assert_eq!(loops.resolve(Some("nonexistent")), None);
+    }
+}
@@ -0,0 +1,348 @@
+//! A `Display` impl for `hir::Ast`, used to dump the monomorphised HIR for
+//! debugging or, with `print_minimal_parens` set, to produce readable,
+//! re-parseable Ante-like source.
+//!
+//! Lambdas are not printed inline: the first time one is encountered it is
+//! given a synthetic name, pushed onto `AstPrinter::queue`, and printed
+//! after the rest of the tree under that name, so that a deeply nested
+//! tree of closures doesn't produce a deeply nested dump.
+use std::collections::VecDeque;
+use std::fmt::{self, Formatter};
+use std::rc::Rc;
+
+use super::{
+    Assignment, Ast, Break, Builtin, Continue, DecisionTree, Definition, Extern, FunctionCall, If,
+    Lambda, Literal, Loop, Match, MemberAccess, ReinterpretCast, Return, Sequence, Tuple, Variable,
+};
+
+#[derive(Default)]
+pub struct AstPrinter {
+    pub queue: VecDeque<(usize, Rc<Ast>)>,
+    next_fn_id: usize,
+
+    /// When set, each node is additionally annotated with the span it was
+    /// lowered from, for debugging the monomorphiser. See `Ast::display_with_spans`.
+    pub print_spans: bool,
+
+    /// When set, a child is only wrapped in parentheses when its
+    /// precedence is lower than the context it's printed in requires -
+    /// producing output that reads like the surface language and
+    /// re-parses to the same tree. When unset (the default, used for
+    /// quick debug dumps), no precedence-based parenthesization is done.
+    pub print_minimal_parens: bool,
+}
+
+pub trait FmtAst {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result;
+}
+
+/// Prints `ast`, wrapping it in parentheses if `printer.print_minimal_parens`
+/// is set and `ast`'s precedence is lower than `min_precedence` - i.e. lower
+/// than what the surrounding context can accept without ambiguity.
+///
+/// A `FunctionCall` that `infix_operator` will render as an infix `Builtin`
+/// (e.g. `a + b`) doesn't bind at `Ast::precedence`'s flat `FunctionCall`
+/// level - it binds at whatever the operator itself does, which can be much
+/// looser (`BitOrInt` is 2). Using the flat level here is what let
+/// `(a + b) * c` print as the unparenthesized, differently-parsed `a + b * c`,
+/// so this looks through to the operator's real precedence first.
+fn fmt_child(ast: &Ast, min_precedence: u8, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+    let precedence = match ast {
+        Ast::FunctionCall(call) => infix_operator(call).map_or_else(|| ast.precedence(), |(_, precedence)| precedence),
+        _ => ast.precedence(),
+    };
+    let needs_parens = printer.print_minimal_parens && precedence < min_precedence;
+    if needs_parens {
+        write!(f, "(")?;
+    }
+    ast.fmt_ast(printer, f)?;
+    if needs_parens {
+        write!(f, ")")?;
+    }
+    Ok(())
+}
+
+/// If `call` is a two-argument call to a `Builtin` with an infix spelling
+/// (`+`, `<<`, `==`, ...), returns that spelling and its precedence so
+/// `FunctionCall`'s printer can render it as `lhs op rhs` instead of the
+/// generic `f a b` prefix form.
+pub(super) fn infix_operator(call: &FunctionCall) -> Option<(&'static str, u8)> {
+    if call.args.len() != 2 {
+        return None;
+    }
+    match call.function.as_ref() {
+        Ast::Builtin(builtin) => builtin.symbol().map(|symbol| (symbol, builtin.precedence())),
+        _ => None,
+    }
+}
+
+impl FmtAst for Ast {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        if printer.print_spans {
+            if let Some(span) = self.span() {
+                write!(f, "/*{}*/ ", span)?;
+            }
+        }
+
+        match self {
+            Ast::Literal(literal) => literal.fmt_ast(printer, f),
+            Ast::Variable(variable) => variable.fmt_ast(printer, f),
+            Ast::Lambda(lambda) => lambda.fmt_ast(printer, f),
+            Ast::FunctionCall(call) => call.fmt_ast(printer, f),
+            Ast::Definition(definition) => definition.fmt_ast(printer, f),
+            Ast::If(if_) => if_.fmt_ast(printer, f),
+            Ast::Match(match_) => match_.fmt_ast(printer, f),
+            Ast::Return(return_) => return_.fmt_ast(printer, f),
+            Ast::Sequence(sequence) => sequence.fmt_ast(printer, f),
+            Ast::Extern(extern_) => extern_.fmt_ast(printer, f),
+            Ast::Assignment(assignment) => assignment.fmt_ast(printer, f),
+            Ast::MemberAccess(member_access) => member_access.fmt_ast(printer, f),
+            Ast::Tuple(tuple) => tuple.fmt_ast(printer, f),
+            Ast::ReinterpretCast(cast) => cast.fmt_ast(printer, f),
+            Ast::Builtin(builtin) => builtin.fmt_ast(printer, f),
+            Ast::Loop(loop_) => loop_.fmt_ast(printer, f),
+            Ast::Break(break_) => break_.fmt_ast(printer, f),
+            Ast::Continue(continue_) => continue_.fmt_ast(printer, f),
+        }
+    }
+}
+
+impl FmtAst for Literal {
+    fn fmt_ast(&self, _printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        match self {
+            Literal::Integer(value, kind) => write!(f, "{}_{:?}", value, kind),
+            Literal::Float(bits) => write!(f, "{}", f64::from_bits(*bits)),
+            Literal::CString(s) => write!(f, "{:?}", s),
+            Literal::Char(c) => write!(f, "'{}'", c),
+            Literal::Bool(b) => write!(f, "{}", b),
+            Literal::Unit => write!(f, "()"),
+        }
+    }
+}
+
+impl FmtAst for Variable {
+    fn fmt_ast(&self, _printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        write!(f, "${}", self.definition_id.0)
+    }
+}
+
+impl FmtAst for Lambda {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        let id = printer.next_fn_id;
+        printer.next_fn_id += 1;
+        printer.queue.push_back((id, Rc::new(Ast::Lambda(self.clone()))));
+        write!(f, "fn_{}", id)
+    }
+}
+
+impl FmtAst for FunctionCall {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        if let Some((symbol, precedence)) = infix_operator(self) {
+            fmt_child(&self.args[0], precedence, printer, f)?;
+            write!(f, " {} ", symbol)?;
+            // Require the right operand to bind one level tighter than this
+            // operator so e.g. `a - (b - c)` keeps its parentheses instead of
+            // printing as `a - b - c`, which re-parses as `(a - b) - c`.
+            fmt_child(&self.args[1], precedence + 1, printer, f)
+        } else {
+            fmt_child(&self.function, 10, printer, f)?;
+            for arg in &self.args {
+                write!(f, " ")?;
+                fmt_child(arg, 10, printer, f)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+impl FmtAst for Definition {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        if self.mutable {
+            write!(f, "mut ")?;
+        }
+        write!(f, "${} = ", self.variable.0)?;
+        fmt_child(&self.expr, 1, printer, f)
+    }
+}
+
+impl FmtAst for If {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        write!(f, "if ")?;
+        fmt_child(&self.condition, 1, printer, f)?;
+        write!(f, " then ")?;
+        fmt_child(&self.then, 1, printer, f)?;
+        if let Some(otherwise) = &self.otherwise {
+            write!(f, " else ")?;
+            fmt_child(otherwise, 1, printer, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FmtAst for Match {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        write!(f, "match {{")?;
+        self.decision_tree.fmt_ast(printer, f)?;
+        write!(f, "}}")
+    }
+}
+
+impl FmtAst for DecisionTree {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        match self {
+            DecisionTree::Leaf(index) => write!(f, "leaf{}", index),
+            DecisionTree::Definition(definition, rest) => {
+                definition.fmt_ast(printer, f)?;
+                write!(f, "; ")?;
+                rest.fmt_ast(printer, f)
+            },
+            DecisionTree::Switch { int_to_switch_on, cases, else_case } => {
+                write!(f, "switch ")?;
+                fmt_child(int_to_switch_on, 10, printer, f)?;
+                write!(f, " {{")?;
+                for (tag, case) in cases {
+                    write!(f, " {} => ", tag)?;
+                    case.fmt_ast(printer, f)?;
+                }
+                if let Some(else_case) = else_case {
+                    write!(f, " _ => ")?;
+                    else_case.fmt_ast(printer, f)?;
+                }
+                write!(f, " }}")
+            },
+        }
+    }
+}
+
+impl FmtAst for Return {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        write!(f, "return ")?;
+        fmt_child(&self.expression, 1, printer, f)
+    }
+}
+
+impl FmtAst for Sequence {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        for (i, statement) in self.statements.iter().enumerate() {
+            if i != 0 {
+                write!(f, "\n")?;
+            }
+            statement.fmt_ast(printer, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FmtAst for Extern {
+    fn fmt_ast(&self, _printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        write!(f, "extern {}", self.name)
+    }
+}
+
+impl FmtAst for Assignment {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        fmt_child(&self.lhs, 3, printer, f)?;
+        write!(f, " := ")?;
+        fmt_child(&self.rhs, 2, printer, f)
+    }
+}
+
+impl FmtAst for MemberAccess {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        fmt_child(&self.lhs, 10, printer, f)?;
+        write!(f, ".{}", self.member_index)
+    }
+}
+
+impl FmtAst for Tuple {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        write!(f, "(")?;
+        for (i, field) in self.fields.iter().enumerate() {
+            if i != 0 {
+                write!(f, ", ")?;
+            }
+            fmt_child(field, 1, printer, f)?;
+        }
+        write!(f, ")")
+    }
+}
+
+impl FmtAst for ReinterpretCast {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        fmt_child(&self.lhs, 10, printer, f)?;
+        write!(f, " as {:?}", self.target_type)
+    }
+}
+
+impl FmtAst for Builtin {
+    fn fmt_ast(&self, _printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl FmtAst for Loop {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        write!(f, "loop ")?;
+        if let Some(label) = &self.label {
+            write!(f, "'{}: ", label.0)?;
+        }
+        write!(f, "{{ ")?;
+        self.body.fmt_ast(printer, f)?;
+        write!(f, " }}")
+    }
+}
+
+impl FmtAst for Break {
+    fn fmt_ast(&self, printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        write!(f, "break")?;
+        if let Some(label) = &self.label {
+            write!(f, " '{}", label.0)?;
+        }
+        if let Some(value) = &self.value {
+            write!(f, " ")?;
+            fmt_child(value, 1, printer, f)?;
+        }
+        Ok(())
+    }
+}
+
+impl FmtAst for Continue {
+    fn fmt_ast(&self, _printer: &mut AstPrinter, f: &mut Formatter) -> fmt::Result {
+        write!(f, "continue")?;
+        if let Some(label) = &self.label {
+            write!(f, " '{}", label.0)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hir::types::IntegerKind;
+
+    fn int(n: u64) -> Ast {
+        Ast::Literal(Literal::Integer(n, IntegerKind::I32))
+    }
+
+    fn infix(op: Builtin, lhs: Ast, rhs: Ast) -> Ast {
+        Ast::FunctionCall(FunctionCall { function: Box::new(Ast::Builtin(op)), args: vec![lhs, rhs], span: None })
+    }
+
+    #[test]
+    fn minimal_parens_round_trips_nested_infix_precedence() {
+        // `(a + b) * c`: args[0] of the `*` call is a `FunctionCall` to
+        // `AddInt`, whose precedence (6) is lower than `*`'s (7), so it
+        // must be parenthesized or it re-parses as `a + b * c`.
+        let ast = infix(Builtin::MulInt, infix(Builtin::AddInt, int(1), int(2)), int(3));
+        assert_eq!(ast.display_minimal_parens().to_string(), "(1_I32 + 2_I32) * 3_I32");
+    }
+
+    #[test]
+    fn minimal_parens_omits_unneeded_parens_for_same_precedence_left_assoc() {
+        // `a - b - c` is already left-associative, so it must print without
+        // parentheses rather than as `(a - b) - c`.
+        let ast = infix(Builtin::SubInt, infix(Builtin::SubInt, int(1), int(2)), int(3));
+        assert_eq!(ast.display_minimal_parens().to_string(), "1_I32 - 2_I32 - 3_I32");
+    }
+}
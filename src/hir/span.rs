@@ -0,0 +1,28 @@
+//! Source-span tracking for `hir::Ast`.
+//!
+//! The main Ast carries a `Location` on every node; `hir::Ast` historically
+//! did not, so once `monomorphise` ran any diagnostic a backend raised
+//! (an LLVM verifier failure, a `ReinterpretCast` size mismatch, an
+//! overflowing `Builtin`) had no way to point back at the user's source.
+//! `Span` is the monomorphised, backend-facing stand-in for `Location`:
+//! a `Location` is tied to the file it was parsed from and a lifetime on
+//! the source text, neither of which survive past monomorphisation, so we
+//! copy out just the byte range and a file id instead.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Span {
+    pub file_id: usize,
+    pub start: u32,
+    pub end: u32,
+}
+
+impl Span {
+    pub fn new(file_id: usize, start: u32, end: u32) -> Span {
+        Span { file_id, start, end }
+    }
+}
+
+impl std::fmt::Display for Span {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}:{}..{}", self.file_id, self.start, self.end)
+    }
+}
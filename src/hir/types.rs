@@ -0,0 +1,76 @@
+//! Monomorphised types used by `hir::Ast`. Unlike the main Ast's types,
+//! these contain no generics or type variables - by the time we reach
+//! the `hir`, monomorphisation has already resolved every type down to
+//! a concrete representation the backends can lower directly.
+use serde::{Serialize, Deserialize};
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum IntegerKind {
+    I8,
+    I16,
+    I32,
+    I64,
+    Isz,
+    U8,
+    U16,
+    U32,
+    U64,
+    Usz,
+}
+
+impl IntegerKind {
+    pub fn bit_width(self) -> u32 {
+        match self {
+            IntegerKind::I8 | IntegerKind::U8 => 8,
+            IntegerKind::I16 | IntegerKind::U16 => 16,
+            IntegerKind::I32 | IntegerKind::U32 => 32,
+            IntegerKind::I64 | IntegerKind::U64 => 64,
+            IntegerKind::Isz | IntegerKind::Usz => (std::mem::size_of::<isize>() * 8) as u32,
+        }
+    }
+
+    pub fn is_signed(self) -> bool {
+        matches!(self, IntegerKind::I8 | IntegerKind::I16 | IntegerKind::I32 | IntegerKind::I64 | IntegerKind::Isz)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct FunctionType {
+    pub parameters: Vec<Type>,
+    pub return_type: Box<Type>,
+    pub is_varargs: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Type {
+    Primitive(PrimitiveType),
+    Function(FunctionType),
+    Tuple(Vec<Type>),
+    Pointer,
+}
+
+impl Type {
+    /// The size in bytes of this type's runtime representation, used by
+    /// `monomorphisation::reinterpret_cast` to check that the two sides of
+    /// a `ReinterpretCast`/`Builtin::Transmute` actually line up.
+    pub fn size_in_bytes(&self) -> u32 {
+        match self {
+            Type::Primitive(PrimitiveType::Integer(kind)) => kind.bit_width() / 8,
+            Type::Primitive(PrimitiveType::Float) => 8,
+            Type::Primitive(PrimitiveType::Char) => 4,
+            Type::Primitive(PrimitiveType::Boolean) => 1,
+            Type::Primitive(PrimitiveType::Unit) => 0,
+            Type::Function(_) | Type::Pointer => (std::mem::size_of::<usize>()) as u32,
+            Type::Tuple(fields) => fields.iter().map(Type::size_in_bytes).sum(),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PrimitiveType {
+    Integer(IntegerKind),
+    Float,
+    Char,
+    Boolean,
+    Unit,
+}
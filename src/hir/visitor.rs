@@ -0,0 +1,573 @@
+//! A generic traversal framework for `hir::Ast`, modelled on rustc's
+//! `intravisit`. `Visitor` walks an `&Ast` without changing it; `Folder`
+//! walks an `Ast` by value and rebuilds it, letting a pass rewrite only
+//! the nodes it cares about.
+//!
+//! Every default method body recurses into the node's children, either
+//! directly or through one of the `walk_*` free functions (`walk_ast`,
+//! `walk_lambda`, `walk_decision_tree`), so an implementor only needs to
+//! override the variants it's interested in and the recursion into
+//! children still happens for free.
+use crate::hir::{
+    Ast, Assignment, Break, Continue, DecisionTree, Definition, Extern, FunctionCall, If, Lambda,
+    Literal, Loop, Match, MemberAccess, ReinterpretCast, Return, Sequence, Tuple, Variable,
+};
+
+/// Visits a `hir::Ast` without mutating it.
+pub trait Visitor: Sized {
+    fn visit_ast(&mut self, ast: &Ast) {
+        walk_ast(self, ast);
+    }
+
+    fn visit_literal(&mut self, _literal: &Literal) {}
+
+    fn visit_variable(&mut self, _variable: &Variable) {}
+
+    fn visit_lambda(&mut self, lambda: &Lambda) {
+        walk_lambda(self, lambda);
+    }
+
+    fn visit_function_call(&mut self, call: &FunctionCall) {
+        self.visit_ast(&call.function);
+        for arg in &call.args {
+            self.visit_ast(arg);
+        }
+    }
+
+    fn visit_definition(&mut self, definition: &Definition) {
+        self.visit_ast(&definition.expr);
+    }
+
+    fn visit_if(&mut self, if_: &If) {
+        self.visit_ast(&if_.condition);
+        self.visit_ast(&if_.then);
+        if let Some(otherwise) = &if_.otherwise {
+            self.visit_ast(otherwise);
+        }
+    }
+
+    fn visit_match(&mut self, match_: &Match) {
+        for branch in &match_.branches {
+            self.visit_ast(branch);
+        }
+        walk_decision_tree(self, &match_.decision_tree);
+    }
+
+    fn visit_return(&mut self, return_: &Return) {
+        self.visit_ast(&return_.expression);
+    }
+
+    fn visit_sequence(&mut self, sequence: &Sequence) {
+        for statement in &sequence.statements {
+            self.visit_ast(statement);
+        }
+    }
+
+    fn visit_extern(&mut self, _extern_: &Extern) {}
+
+    fn visit_assignment(&mut self, assignment: &Assignment) {
+        self.visit_ast(&assignment.lhs);
+        self.visit_ast(&assignment.rhs);
+    }
+
+    fn visit_member_access(&mut self, member_access: &MemberAccess) {
+        self.visit_ast(&member_access.lhs);
+    }
+
+    fn visit_tuple(&mut self, tuple: &Tuple) {
+        for field in &tuple.fields {
+            self.visit_ast(field);
+        }
+    }
+
+    fn visit_reinterpret_cast(&mut self, cast: &ReinterpretCast) {
+        self.visit_ast(&cast.lhs);
+    }
+
+    fn visit_builtin(&mut self, _builtin: &crate::hir::Builtin) {}
+
+    fn visit_loop(&mut self, loop_: &Loop) {
+        self.visit_ast(&loop_.body);
+    }
+
+    fn visit_break(&mut self, break_: &Break) {
+        if let Some(value) = &break_.value {
+            self.visit_ast(value);
+        }
+    }
+
+    fn visit_continue(&mut self, _continue_: &Continue) {}
+}
+
+/// Recurses into the children of `ast`, dispatching to the matching
+/// `visit_*` method on `visitor`. Call this from an overridden `visit_ast`
+/// to get the default recursive behavior back.
+pub fn walk_ast<V: Visitor>(visitor: &mut V, ast: &Ast) {
+    match ast {
+        Ast::Literal(literal) => visitor.visit_literal(literal),
+        Ast::Variable(variable) => visitor.visit_variable(variable),
+        Ast::Lambda(lambda) => visitor.visit_lambda(lambda),
+        Ast::FunctionCall(call) => visitor.visit_function_call(call),
+        Ast::Definition(definition) => visitor.visit_definition(definition),
+        Ast::If(if_) => visitor.visit_if(if_),
+        Ast::Match(match_) => visitor.visit_match(match_),
+        Ast::Return(return_) => visitor.visit_return(return_),
+        Ast::Sequence(sequence) => visitor.visit_sequence(sequence),
+        Ast::Extern(extern_) => visitor.visit_extern(extern_),
+        Ast::Assignment(assignment) => visitor.visit_assignment(assignment),
+        Ast::MemberAccess(member_access) => visitor.visit_member_access(member_access),
+        Ast::Tuple(tuple) => visitor.visit_tuple(tuple),
+        Ast::ReinterpretCast(cast) => visitor.visit_reinterpret_cast(cast),
+        Ast::Builtin(builtin) => visitor.visit_builtin(builtin),
+        Ast::Loop(loop_) => visitor.visit_loop(loop_),
+        Ast::Break(break_) => visitor.visit_break(break_),
+        Ast::Continue(continue_) => visitor.visit_continue(continue_),
+    }
+}
+
+pub fn walk_lambda<V: Visitor>(visitor: &mut V, lambda: &Lambda) {
+    for arg in &lambda.args {
+        visitor.visit_ast(arg);
+    }
+    visitor.visit_ast(&lambda.body);
+}
+
+pub fn walk_decision_tree<V: Visitor>(visitor: &mut V, tree: &DecisionTree) {
+    match tree {
+        DecisionTree::Leaf(_) => (),
+        DecisionTree::Definition(definition, rest) => {
+            visitor.visit_definition(definition);
+            walk_decision_tree(visitor, rest);
+        },
+        DecisionTree::Switch { int_to_switch_on, cases, else_case } => {
+            visitor.visit_ast(int_to_switch_on);
+            for (_, case) in cases {
+                walk_decision_tree(visitor, case);
+            }
+            if let Some(else_case) = else_case {
+                walk_decision_tree(visitor, else_case);
+            }
+        },
+    }
+}
+
+/// Rewrites a `hir::Ast` by value, producing a new tree. Like `Visitor`,
+/// every default method delegates to a `walk_*`-equivalent fold so an
+/// implementor can override a single variant and still get the rest of
+/// the tree folded underneath it.
+pub trait Folder: Sized {
+    fn fold_ast(&mut self, ast: Ast) -> Ast {
+        fold_ast(self, ast)
+    }
+
+    fn fold_literal(&mut self, literal: Literal) -> Ast {
+        Ast::Literal(literal)
+    }
+
+    fn fold_variable(&mut self, variable: Variable) -> Ast {
+        Ast::Variable(variable)
+    }
+
+    fn fold_lambda(&mut self, lambda: Lambda) -> Ast {
+        Ast::Lambda(fold_lambda(self, lambda))
+    }
+
+    fn fold_function_call(&mut self, call: FunctionCall) -> Ast {
+        let function = Box::new(self.fold_ast(*call.function));
+        let args = call.args.into_iter().map(|arg| self.fold_ast(arg)).collect();
+        Ast::FunctionCall(FunctionCall { function, args, span: call.span })
+    }
+
+    fn fold_definition(&mut self, definition: Definition) -> Ast {
+        Ast::Definition(fold_definition(self, definition))
+    }
+
+    fn fold_if(&mut self, if_: If) -> Ast {
+        let condition = Box::new(self.fold_ast(*if_.condition));
+        let then = Box::new(self.fold_ast(*if_.then));
+        let otherwise = if_.otherwise.map(|otherwise| Box::new(self.fold_ast(*otherwise)));
+        Ast::If(If { condition, then, otherwise, span: if_.span })
+    }
+
+    fn fold_match(&mut self, match_: Match) -> Ast {
+        let branches = match_.branches.into_iter().map(|branch| self.fold_ast(branch)).collect();
+        let decision_tree = fold_decision_tree(self, match_.decision_tree);
+        Ast::Match(Match { branches, decision_tree })
+    }
+
+    fn fold_return(&mut self, return_: Return) -> Ast {
+        let expression = Box::new(self.fold_ast(*return_.expression));
+        Ast::Return(Return { expression, span: return_.span })
+    }
+
+    fn fold_sequence(&mut self, sequence: Sequence) -> Ast {
+        let statements = sequence.statements.into_iter().map(|stmt| self.fold_ast(stmt)).collect();
+        Ast::Sequence(Sequence { statements })
+    }
+
+    fn fold_extern(&mut self, extern_: Extern) -> Ast {
+        Ast::Extern(extern_)
+    }
+
+    fn fold_assignment(&mut self, assignment: Assignment) -> Ast {
+        let lhs = Box::new(self.fold_ast(*assignment.lhs));
+        let rhs = Box::new(self.fold_ast(*assignment.rhs));
+        Ast::Assignment(Assignment { lhs, rhs })
+    }
+
+    fn fold_member_access(&mut self, member_access: MemberAccess) -> Ast {
+        let lhs = Box::new(self.fold_ast(*member_access.lhs));
+        Ast::MemberAccess(MemberAccess { lhs, member_index: member_access.member_index })
+    }
+
+    fn fold_tuple(&mut self, tuple: Tuple) -> Ast {
+        let fields = tuple.fields.into_iter().map(|field| self.fold_ast(field)).collect();
+        Ast::Tuple(Tuple { fields })
+    }
+
+    fn fold_reinterpret_cast(&mut self, cast: ReinterpretCast) -> Ast {
+        let lhs = Box::new(self.fold_ast(*cast.lhs));
+        Ast::ReinterpretCast(ReinterpretCast { lhs, target_type: cast.target_type, span: cast.span })
+    }
+
+    fn fold_builtin(&mut self, builtin: crate::hir::Builtin) -> Ast {
+        Ast::Builtin(builtin)
+    }
+
+    fn fold_loop(&mut self, loop_: Loop) -> Ast {
+        let body = Box::new(self.fold_ast(*loop_.body));
+        Ast::Loop(Loop { label: loop_.label, body })
+    }
+
+    fn fold_break(&mut self, break_: Break) -> Ast {
+        let value = break_.value.map(|value| Box::new(self.fold_ast(*value)));
+        Ast::Break(Break { label: break_.label, value })
+    }
+
+    fn fold_continue(&mut self, continue_: Continue) -> Ast {
+        Ast::Continue(continue_)
+    }
+}
+
+pub fn fold_ast<F: Folder>(folder: &mut F, ast: Ast) -> Ast {
+    match ast {
+        Ast::Literal(literal) => folder.fold_literal(literal),
+        Ast::Variable(variable) => folder.fold_variable(variable),
+        Ast::Lambda(lambda) => folder.fold_lambda(lambda),
+        Ast::FunctionCall(call) => folder.fold_function_call(call),
+        Ast::Definition(definition) => folder.fold_definition(definition),
+        Ast::If(if_) => folder.fold_if(if_),
+        Ast::Match(match_) => folder.fold_match(match_),
+        Ast::Return(return_) => folder.fold_return(return_),
+        Ast::Sequence(sequence) => folder.fold_sequence(sequence),
+        Ast::Extern(extern_) => folder.fold_extern(extern_),
+        Ast::Assignment(assignment) => folder.fold_assignment(assignment),
+        Ast::MemberAccess(member_access) => folder.fold_member_access(member_access),
+        Ast::Tuple(tuple) => folder.fold_tuple(tuple),
+        Ast::ReinterpretCast(cast) => folder.fold_reinterpret_cast(cast),
+        Ast::Builtin(builtin) => folder.fold_builtin(builtin),
+        Ast::Loop(loop_) => folder.fold_loop(loop_),
+        Ast::Break(break_) => folder.fold_break(break_),
+        Ast::Continue(continue_) => folder.fold_continue(continue_),
+    }
+}
+
+fn fold_lambda<F: Folder>(folder: &mut F, lambda: Lambda) -> Lambda {
+    let args = lambda.args.into_iter().map(|arg| folder.fold_ast(arg)).collect();
+    let body = Box::new(folder.fold_ast(*lambda.body));
+    Lambda { args, body, typ: lambda.typ, span: lambda.span }
+}
+
+fn fold_definition<F: Folder>(folder: &mut F, definition: Definition) -> Definition {
+    let expr = Box::new(folder.fold_ast(*definition.expr));
+    Definition { variable: definition.variable, expr, mutable: definition.mutable }
+}
+
+fn fold_decision_tree<F: Folder>(folder: &mut F, tree: DecisionTree) -> DecisionTree {
+    match tree {
+        DecisionTree::Leaf(index) => DecisionTree::Leaf(index),
+        DecisionTree::Definition(definition, rest) => {
+            let definition = fold_definition(folder, definition);
+            let rest = Box::new(fold_decision_tree(folder, *rest));
+            DecisionTree::Definition(definition, rest)
+        },
+        DecisionTree::Switch { int_to_switch_on, cases, else_case } => {
+            let int_to_switch_on = Box::new(folder.fold_ast(*int_to_switch_on));
+            let cases = cases.into_iter().map(|(tag, case)| (tag, fold_decision_tree(folder, case))).collect();
+            let else_case = else_case.map(|case| Box::new(fold_decision_tree(folder, *case)));
+            DecisionTree::Switch { int_to_switch_on, cases, else_case }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hir::types::{FunctionType, IntegerKind, Type};
+    use crate::hir::{Builtin, DefinitionId, LoopId};
+
+    fn var(id: usize) -> Variable {
+        Variable { definition_id: DefinitionId(id), definition: None }
+    }
+
+    /// One `Ast` built to contain every variant at least once, including a
+    /// `Match` whose `decision_tree` reaches every `DecisionTree` variant
+    /// and a `Loop`/`Break`/`Continue` nest - so a single walk/fold over it
+    /// can confirm the traversal framework actually reaches everything
+    /// instead of only the variants whichever pass happened to exercise.
+    fn every_variant() -> Ast {
+        let decision_tree = DecisionTree::Switch {
+            int_to_switch_on: Box::new(Ast::Literal(Literal::Integer(0, IntegerKind::I32))),
+            cases: vec![(
+                0,
+                DecisionTree::Definition(
+                    Definition { variable: DefinitionId(1), expr: Box::new(Ast::Literal(Literal::Unit)), mutable: false },
+                    Box::new(DecisionTree::Leaf(0)),
+                ),
+            )],
+            else_case: Some(Box::new(DecisionTree::Leaf(1))),
+        };
+
+        Ast::Sequence(Sequence {
+            statements: vec![
+                Ast::Definition(Definition {
+                    variable: DefinitionId(0),
+                    expr: Box::new(Ast::Lambda(Lambda {
+                        args: vec![Ast::Variable(var(0))],
+                        body: Box::new(Ast::If(If {
+                            condition: Box::new(Ast::Literal(Literal::Bool(true))),
+                            then: Box::new(Ast::FunctionCall(FunctionCall {
+                                function: Box::new(Ast::Builtin(Builtin::AddInt)),
+                                args: vec![Ast::Tuple(Tuple { fields: vec![Ast::Literal(Literal::Unit)] })],
+                                span: None,
+                            })),
+                            otherwise: Some(Box::new(Ast::Return(Return {
+                                expression: Box::new(Ast::MemberAccess(MemberAccess {
+                                    lhs: Box::new(Ast::Variable(var(0))),
+                                    member_index: 0,
+                                })),
+                                span: None,
+                            }))),
+                            span: None,
+                        })),
+                        typ: FunctionType { parameters: Vec::new(), return_type: Box::new(Type::Pointer), is_varargs: false },
+                        span: None,
+                    })),
+                    mutable: false,
+                }),
+                Ast::Assignment(Assignment {
+                    lhs: Box::new(Ast::Variable(var(0))),
+                    rhs: Box::new(Ast::ReinterpretCast(ReinterpretCast {
+                        lhs: Box::new(Ast::Literal(Literal::Unit)),
+                        target_type: Type::Pointer,
+                        span: None,
+                    })),
+                }),
+                Ast::Match(Match { branches: vec![Ast::Literal(Literal::Unit)], decision_tree }),
+                Ast::Loop(Loop {
+                    label: Some(LoopId(0)),
+                    body: Box::new(Ast::Sequence(Sequence {
+                        statements: vec![
+                            Ast::Break(Break { label: Some(LoopId(0)), value: Some(Box::new(Ast::Literal(Literal::Unit))) }),
+                            Ast::Continue(Continue { label: Some(LoopId(0)) }),
+                        ],
+                    })),
+                }),
+                Ast::Extern(Extern { name: "e".to_string(), typ: Type::Pointer }),
+            ],
+        })
+    }
+
+    #[derive(Default)]
+    struct CountingVisitor {
+        literals: usize,
+        variables: usize,
+        lambdas: usize,
+        function_calls: usize,
+        definitions: usize,
+        ifs: usize,
+        matches: usize,
+        returns: usize,
+        sequences: usize,
+        externs: usize,
+        assignments: usize,
+        member_accesses: usize,
+        tuples: usize,
+        reinterpret_casts: usize,
+        builtins: usize,
+        loops: usize,
+        breaks: usize,
+        continues: usize,
+        decision_tree_leaves: usize,
+        decision_tree_switches: usize,
+    }
+
+    impl Visitor for CountingVisitor {
+        fn visit_literal(&mut self, _literal: &Literal) {
+            self.literals += 1;
+        }
+
+        fn visit_variable(&mut self, _variable: &Variable) {
+            self.variables += 1;
+        }
+
+        fn visit_lambda(&mut self, lambda: &Lambda) {
+            self.lambdas += 1;
+            walk_lambda(self, lambda);
+        }
+
+        fn visit_function_call(&mut self, call: &FunctionCall) {
+            self.function_calls += 1;
+            self.visit_ast(&call.function);
+            for arg in &call.args {
+                self.visit_ast(arg);
+            }
+        }
+
+        fn visit_definition(&mut self, definition: &Definition) {
+            self.definitions += 1;
+            self.visit_ast(&definition.expr);
+        }
+
+        fn visit_if(&mut self, if_: &If) {
+            self.ifs += 1;
+            self.visit_ast(&if_.condition);
+            self.visit_ast(&if_.then);
+            if let Some(otherwise) = &if_.otherwise {
+                self.visit_ast(otherwise);
+            }
+        }
+
+        fn visit_match(&mut self, match_: &Match) {
+            self.matches += 1;
+            for branch in &match_.branches {
+                self.visit_ast(branch);
+            }
+            walk_decision_tree(self, &match_.decision_tree);
+        }
+
+        fn visit_return(&mut self, return_: &Return) {
+            self.returns += 1;
+            self.visit_ast(&return_.expression);
+        }
+
+        fn visit_sequence(&mut self, sequence: &Sequence) {
+            self.sequences += 1;
+            for statement in &sequence.statements {
+                self.visit_ast(statement);
+            }
+        }
+
+        fn visit_extern(&mut self, _extern_: &Extern) {
+            self.externs += 1;
+        }
+
+        fn visit_assignment(&mut self, assignment: &Assignment) {
+            self.assignments += 1;
+            self.visit_ast(&assignment.lhs);
+            self.visit_ast(&assignment.rhs);
+        }
+
+        fn visit_member_access(&mut self, member_access: &MemberAccess) {
+            self.member_accesses += 1;
+            self.visit_ast(&member_access.lhs);
+        }
+
+        fn visit_tuple(&mut self, tuple: &Tuple) {
+            self.tuples += 1;
+            for field in &tuple.fields {
+                self.visit_ast(field);
+            }
+        }
+
+        fn visit_reinterpret_cast(&mut self, cast: &ReinterpretCast) {
+            self.reinterpret_casts += 1;
+            self.visit_ast(&cast.lhs);
+        }
+
+        fn visit_builtin(&mut self, _builtin: &crate::hir::Builtin) {
+            self.builtins += 1;
+        }
+
+        fn visit_loop(&mut self, loop_: &Loop) {
+            self.loops += 1;
+            self.visit_ast(&loop_.body);
+        }
+
+        fn visit_break(&mut self, break_: &Break) {
+            self.breaks += 1;
+            if let Some(value) = &break_.value {
+                self.visit_ast(value);
+            }
+        }
+
+        fn visit_continue(&mut self, _continue_: &Continue) {
+            self.continues += 1;
+        }
+    }
+
+    #[test]
+    fn visitor_reaches_every_ast_and_decision_tree_variant() {
+        let ast = every_variant();
+        let mut visitor = CountingVisitor::default();
+        visitor.visit_ast(&ast);
+
+        assert!(visitor.literals >= 1);
+        assert!(visitor.variables >= 1);
+        assert_eq!(visitor.lambdas, 1);
+        assert_eq!(visitor.function_calls, 1);
+        assert_eq!(visitor.definitions, 2); // top-level + the one inside the decision tree
+        assert_eq!(visitor.ifs, 1);
+        assert_eq!(visitor.matches, 1);
+        assert_eq!(visitor.returns, 1);
+        assert_eq!(visitor.sequences, 2); // the top-level one + the loop body
+        assert_eq!(visitor.externs, 1);
+        assert_eq!(visitor.assignments, 1);
+        assert_eq!(visitor.member_accesses, 1);
+        assert_eq!(visitor.tuples, 1);
+        assert_eq!(visitor.reinterpret_casts, 1);
+        assert_eq!(visitor.builtins, 1);
+        assert_eq!(visitor.loops, 1);
+        assert_eq!(visitor.breaks, 1);
+        assert_eq!(visitor.continues, 1);
+    }
+
+    /// A `Folder` that replaces every `Literal::Unit` with an integer
+    /// literal, used to confirm `fold_ast`/`fold_decision_tree` reach every
+    /// nested `Ast`, not just the top-level statements.
+    struct UnitToInt;
+
+    impl Folder for UnitToInt {
+        fn fold_literal(&mut self, literal: Literal) -> Ast {
+            match literal {
+                Literal::Unit => Ast::Literal(Literal::Integer(1, IntegerKind::I32)),
+                other => Ast::Literal(other),
+            }
+        }
+    }
+
+    fn count_int_literals(ast: &Ast) -> usize {
+        struct CountIntLiterals(usize);
+        impl Visitor for CountIntLiterals {
+            fn visit_literal(&mut self, literal: &Literal) {
+                if matches!(literal, Literal::Integer(..)) {
+                    self.0 += 1;
+                }
+            }
+        }
+        let mut counter = CountIntLiterals(0);
+        counter.visit_ast(ast);
+        counter.0
+    }
+
+    #[test]
+    fn folder_rewrites_every_nested_unit_literal() {
+        let ast = every_variant();
+        let before = count_int_literals(&ast);
+
+        let mut folder = UnitToInt;
+        let folded = folder.fold_ast(ast);
+
+        assert!(count_int_literals(&folded) > before);
+    }
+}